@@ -1,19 +1,34 @@
-use std::cmp::{Ordering, Reverse};
-use std::collections::{BinaryHeap, VecDeque};
+// pyo3 0.20's #[pymethods] expansion trips `non_local_definitions` under
+// newer rustc versions; nothing here to fix on our end.
+#![allow(non_local_definitions)]
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyList};
+use pyo3::types::{PyBytes, PyDict, PyList};
+use pyo3::wrap_pyfunction;
 use rand::prelude::*;
-use rand::rngs::StdRng;
+use rand_chacha::ChaCha12Rng;
 use rand_distr::{Distribution, Exp};
+use serde::{Deserialize, Serialize};
 
 const STATUS_IDLE: u8 = 0;
 const STATUS_WORKING: u8 = 1;
 const STATUS_BLOCKED: u8 = 2;
 const STATUS_DOWN: u8 = 3;
+const STATUS_MAINT: u8 = 4;
 
-#[derive(Clone)]
+const STREAM_RUNNING: u8 = 0;
+const STREAM_PAUSED: u8 = 1;
+const STREAM_CANCELLED: u8 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
 struct Station {
     status: u8,
     starved: bool,
@@ -23,6 +38,32 @@ struct Station {
     job_id: Option<usize>,
     repairing: bool,
     repair_eta: Option<f64>,
+    /// Set as soon as this station is claimed for preventive maintenance
+    /// (queued or actively under maintenance), whether by the scheduled
+    /// interval or a condition-based trigger; cleared when maintenance
+    /// completes.
+    maint_pending: bool,
+    /// Set while an immediate condition-based `MaintenanceDue` event is in
+    /// flight for this station, so `check_condition_maintenance` doesn't
+    /// keep re-scheduling one every tick the threshold stays crossed.
+    maint_requested: bool,
+    /// Cumulative time spent `STATUS_WORKING` since maintenance last reset
+    /// it, used both to age `effective_fail_rate` and as the condition-based
+    /// maintenance trigger.
+    busy_time_since_maint: f64,
+    /// Per-station failure probability used by `apply_action`; equals the
+    /// sim-wide `fail_rate` baseline and creeps upward with `wear_rate` as
+    /// `busy_time_since_maint` grows, reset back to baseline by maintenance.
+    effective_fail_rate: f64,
+    /// Set when a failure or maintenance preemption evicts this station's
+    /// in-progress part but the upstream buffer (refilled by upstream flow
+    /// while this station sat down/in maintenance) has no room to take it
+    /// back. The part stays with the station instead of overflowing the
+    /// buffer; `apply_action` resumes it directly once the station goes
+    /// idle again, rather than re-queuing it through the buffer (which
+    /// could deadlock against that same full buffer having no other
+    /// drain).
+    held_part: bool,
 }
 
 impl Station {
@@ -36,15 +77,22 @@ impl Station {
             job_id: None,
             repairing: false,
             repair_eta: None,
+            maint_pending: false,
+            maint_requested: false,
+            busy_time_since_maint: 0.0,
+            effective_fail_rate: 0.0,
+            held_part: false,
         }
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum EventType {
     ServiceComplete,
     MachineFailure,
     RepairComplete,
+    MaintenanceDue,
+    MaintenanceComplete,
 }
 
 impl EventType {
@@ -53,11 +101,31 @@ impl EventType {
             EventType::ServiceComplete => "service_complete",
             EventType::MachineFailure => "machine_failure",
             EventType::RepairComplete => "repair_complete",
+            EventType::MaintenanceDue => "maintenance_due",
+            EventType::MaintenanceComplete => "maintenance_complete",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum RepairPolicy {
+    Fifo,
+    Bottleneck,
+}
+
+impl RepairPolicy {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s {
+            "fifo" => Ok(RepairPolicy::Fifo),
+            "bottleneck" => Ok(RepairPolicy::Bottleneck),
+            other => Err(PyValueError::new_err(format!(
+                "repair_policy must be 'fifo' or 'bottleneck', got '{other}'"
+            ))),
         }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct Event {
     t: f64,
     seq: u64,
@@ -88,7 +156,171 @@ impl PartialOrd for Event {
     }
 }
 
+const CALENDAR_MIN_BUCKETS: usize = 8;
+const CALENDAR_DEFAULT_WIDTH: f64 = 1.0;
+
+/// Future-event set backed by a calendar queue (Brown, 1988): an array of
+/// time-sorted buckets indexed by `floor(t / bucket_width) mod n_buckets`.
+/// Enqueue/dequeue are O(1) amortized as long as each bucket holds ~1 event,
+/// which `resize` maintains by doubling/halving `n_buckets` and recomputing
+/// `bucket_width` from the average gap between *sorted* pending event
+/// times (not the order events happen to be scheduled in -- a single
+/// `apply_action` pass enqueues events for unrelated future times in
+/// whatever order stations are visited, so that order isn't informative
+/// about how densely events are actually spaced).
+#[derive(Clone, Serialize, Deserialize)]
+struct CalendarQueue {
+    buckets: Vec<Vec<Event>>,
+    n_buckets: usize,
+    bucket_width: f64,
+    current_bucket: usize,
+    /// Upper time bound of `current_bucket` for the in-progress rotation
+    /// ("year"); advances by `bucket_width` each time the scan steps to the
+    /// next bucket, and keeps growing across a full cycle until the true
+    /// minimum is uncovered.
+    year_top: f64,
+    len: usize,
+}
+
+impl CalendarQueue {
+    fn new(n_buckets: usize, bucket_width: f64) -> Self {
+        let n_buckets = n_buckets.max(CALENDAR_MIN_BUCKETS);
+        let bucket_width = bucket_width.max(1e-6);
+        Self {
+            buckets: vec![Vec::new(); n_buckets],
+            n_buckets,
+            bucket_width,
+            current_bucket: 0,
+            year_top: bucket_width,
+            len: 0,
+        }
+    }
+
+    fn bucket_index(&self, t: f64) -> usize {
+        let slot = (t / self.bucket_width).floor() as i64;
+        slot.rem_euclid(self.n_buckets as i64) as usize
+    }
+
+    fn push(&mut self, evt: Event) {
+        let idx = self.bucket_index(evt.t);
+        let bucket = &mut self.buckets[idx];
+        let pos = bucket.partition_point(|e| *e < evt);
+        bucket.insert(pos, evt);
+        self.len += 1;
+
+        if self.len > 2 * self.n_buckets {
+            self.resize(self.n_buckets * 2);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        loop {
+            if let Some(first) = self.buckets[self.current_bucket].first() {
+                if first.t < self.year_top {
+                    let evt = self.buckets[self.current_bucket].remove(0);
+                    self.len -= 1;
+                    if self.n_buckets > CALENDAR_MIN_BUCKETS && self.len < self.n_buckets / 2 {
+                        self.resize((self.n_buckets / 2).max(CALENDAR_MIN_BUCKETS));
+                    }
+                    return Some(evt);
+                }
+            }
+            self.current_bucket = (self.current_bucket + 1) % self.n_buckets;
+            self.year_top += self.bucket_width;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// True if some pending event matches `(etype, sid)`; used by
+    /// `check_invariants` to confirm a working station really does have a
+    /// `ServiceComplete` in flight rather than trusting derived state.
+    fn contains(&self, etype: EventType, sid: usize) -> bool {
+        self.buckets
+            .iter()
+            .flatten()
+            .any(|e| e.etype == etype && e.sid == sid)
+    }
+
+    /// Time of the next event without removing it, for callers that need to
+    /// pace themselves against it (e.g. `run_realtime`). Mirrors `pop`'s
+    /// bucket-rotation scan but over local copies of the cursor, so it
+    /// leaves the queue's actual rotation state untouched.
+    fn peek_time(&self) -> Option<f64> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut cursor = self.current_bucket;
+        let mut year_top = self.year_top;
+        loop {
+            if let Some(first) = self.buckets[cursor].first() {
+                if first.t < year_top {
+                    return Some(first.t);
+                }
+            }
+            cursor = (cursor + 1) % self.n_buckets;
+            year_top += self.bucket_width;
+        }
+    }
+
+    fn clear(&mut self) {
+        for bucket in &mut self.buckets {
+            bucket.clear();
+        }
+        self.len = 0;
+        self.current_bucket = 0;
+        self.year_top = self.bucket_width;
+    }
+
+    /// Re-buckets every pending event into `new_n_buckets` buckets, deriving
+    /// `bucket_width` from the average gap between consecutive pending
+    /// event times *in sorted order* so each bucket again holds ~1 event.
+    fn resize(&mut self, new_n_buckets: usize) {
+        // `year_top - bucket_width` is the lower bound of the bucket `pop`
+        // is currently scanning from, i.e. the best estimate of "now" we
+        // have when there's nothing pending to anchor to instead.
+        let old_reference_time = self.year_top - self.bucket_width;
+
+        let mut all: Vec<Event> = self.buckets.iter_mut().flat_map(|b| b.drain(..)).collect();
+        all.sort_unstable();
+
+        let new_width = if all.len() > 1 {
+            let span = all.last().unwrap().t - all.first().unwrap().t;
+            (span / (all.len() - 1) as f64).max(1e-6)
+        } else {
+            self.bucket_width
+        };
+
+        // Anchor the rotation to the smallest pending event (or, if the
+        // queue is empty, to where it was scanning before) instead of
+        // always restarting at bucket 0 / year_top = bucket_width. Resetting
+        // unconditionally forced `pop`'s scan loop to walk forward one
+        // bucket-width at a time from time zero until it caught back up to
+        // the real minimum -- a cost proportional to elapsed time, not O(1).
+        let reference_time = all.first().map(|e| e.t).unwrap_or(old_reference_time);
+
+        self.n_buckets = new_n_buckets.max(CALENDAR_MIN_BUCKETS);
+        self.bucket_width = new_width;
+        self.buckets = vec![Vec::new(); self.n_buckets];
+
+        let slot = (reference_time / self.bucket_width).floor();
+        self.current_bucket = (slot as i64).rem_euclid(self.n_buckets as i64) as usize;
+        self.year_top = (slot + 1.0) * self.bucket_width;
+
+        for evt in all {
+            let idx = self.bucket_index(evt.t);
+            self.buckets[idx].push(evt);
+        }
+    }
+}
+
 #[pyclass]
+#[derive(Clone, Serialize, Deserialize)]
 struct FactorySim {
     n_stations: usize,
     buffer_caps: Vec<usize>,
@@ -100,9 +332,10 @@ struct FactorySim {
     workers_total: usize,
     workers_available: usize,
     repair_queue: VecDeque<usize>,
-    rng: StdRng,
+    repair_policy: RepairPolicy,
+    rng: ChaCha12Rng,
     time: f64,
-    event_queue: BinaryHeap<Reverse<Event>>,
+    event_queue: CalendarQueue,
     seq: u64,
     current_speed: f64,
     #[pyo3(get)]
@@ -119,11 +352,44 @@ struct FactorySim {
     t_last_decision: f64,
     last_event_type: Option<EventType>,
     last_event_sid: Option<usize>,
+    /// Base preventive-maintenance interval; `None` disables the subsystem
+    /// entirely (every station just runs fail_rate-driven reactive repair).
+    maint_interval: Option<f64>,
+    /// Upper bound of the `uniform(0, jitter)` added to `maint_interval` so
+    /// stations desynchronize instead of all coming due at once.
+    maint_jitter: f64,
+    /// Planned downtime duration for a single maintenance visit.
+    maint_time: f64,
+    /// Condition-based trigger: bring maintenance forward once a station's
+    /// `util_ema` reaches this level.
+    maint_util_threshold: Option<f64>,
+    /// Condition-based trigger: bring maintenance forward once a station's
+    /// cumulative busy time since its last maintenance reaches this level.
+    maint_busy_threshold: Option<f64>,
+    /// How fast a station's `effective_fail_rate` climbs per unit of busy
+    /// time since its last maintenance; 0.0 disables wear entirely.
+    wear_rate: f64,
+    maintenance_downtime: f64,
+    planned_downtime_count: usize,
+    unplanned_downtime_count: usize,
+    /// Pause/resume/cancel flag for `run_realtime`, checked every loop
+    /// iteration so a `StreamControl` handle's `pause`/`resume`/`cancel`
+    /// (called from another thread while the GIL is released) can steer a stream
+    /// in progress. Runtime-only: never serialized, always fresh on
+    /// `new`/`reset`/deserialize.
+    #[serde(skip, default = "FactorySim::fresh_stream_control")]
+    stream_control: Arc<AtomicU8>,
 }
 
 #[pymethods]
 impl FactorySim {
+    #[allow(clippy::too_many_arguments)]
     #[new]
+    #[pyo3(signature = (
+        n_stations, buffer_caps, proc_means, proc_dists, util_alpha, fail_rate, repair_time,
+        workers, repair_policy=None, maint_interval=None, maint_jitter=0.0, maint_time=1.0,
+        maint_util_threshold=None, maint_busy_threshold=None, wear_rate=0.0
+    ))]
     fn new(
         n_stations: usize,
         buffer_caps: Vec<usize>,
@@ -133,6 +399,13 @@ impl FactorySim {
         fail_rate: f64,
         repair_time: f64,
         workers: usize,
+        repair_policy: Option<String>,
+        maint_interval: Option<f64>,
+        maint_jitter: f64,
+        maint_time: f64,
+        maint_util_threshold: Option<f64>,
+        maint_busy_threshold: Option<f64>,
+        wear_rate: f64,
     ) -> PyResult<Self> {
         if n_stations < 1 {
             return Err(PyValueError::new_err("Need at least one station"));
@@ -152,6 +425,20 @@ impl FactorySim {
                 "proc_dists length must equal n_stations",
             ));
         }
+        if let Some(iv) = maint_interval {
+            if iv <= 0.0 {
+                return Err(PyValueError::new_err("maint_interval must be positive"));
+            }
+        }
+        let repair_policy = match repair_policy {
+            Some(s) => RepairPolicy::parse(&s)?,
+            None => RepairPolicy::Fifo,
+        };
+
+        let mut stations: Vec<Station> = (0..n_stations).map(|_| Station::new()).collect();
+        for st in &mut stations {
+            st.effective_fail_rate = fail_rate;
+        }
 
         Ok(Self {
             n_stations,
@@ -164,9 +451,10 @@ impl FactorySim {
             workers_total: workers,
             workers_available: workers,
             repair_queue: VecDeque::new(),
-            rng: StdRng::from_entropy(),
+            repair_policy,
+            rng: ChaCha12Rng::from_entropy(),
             time: 0.0,
-            event_queue: BinaryHeap::new(),
+            event_queue: CalendarQueue::new(CALENDAR_MIN_BUCKETS, CALENDAR_DEFAULT_WIDTH),
             seq: 0,
             current_speed: 1.0,
             jobs_total: 0,
@@ -175,21 +463,40 @@ impl FactorySim {
             wip_history: Vec::new(),
             record_history: true,
             buffers: vec![0; n_stations.saturating_sub(1)],
-            stations: (0..n_stations).map(|_| Station::new()).collect(),
+            stations,
             throughput_total: 0,
             throughput_since_decision: 0,
             t_last_decision: 0.0,
             last_event_type: None,
             last_event_sid: None,
+            maint_interval,
+            maint_jitter,
+            maint_time,
+            maint_util_threshold,
+            maint_busy_threshold,
+            wear_rate,
+            maintenance_downtime: 0.0,
+            planned_downtime_count: 0,
+            unplanned_downtime_count: 0,
+            stream_control: Self::fresh_stream_control(),
         })
     }
 
-    #[pyo3(signature = (seed=None, n_jobs=100))]
-    fn reset(&mut self, py: Python, seed: Option<u64>, n_jobs: usize) -> PyResult<PyObject> {
+    #[pyo3(signature = (seed=None, n_jobs=100, repair_policy=None))]
+    fn reset(
+        &mut self,
+        py: Python,
+        seed: Option<u64>,
+        n_jobs: usize,
+        repair_policy: Option<String>,
+    ) -> PyResult<PyObject> {
         self.rng = match seed {
-            Some(v) => StdRng::seed_from_u64(v),
-            None => StdRng::from_entropy(),
+            Some(v) => ChaCha12Rng::seed_from_u64(v),
+            None => ChaCha12Rng::from_entropy(),
         };
+        if let Some(s) = repair_policy {
+            self.repair_policy = RepairPolicy::parse(&s)?;
+        }
         self.time = 0.0;
         self.event_queue.clear();
         self.seq = 0;
@@ -200,6 +507,9 @@ impl FactorySim {
         self.repair_queue.clear();
         self.buffers = vec![0; self.n_stations.saturating_sub(1)];
         self.stations = (0..self.n_stations).map(|_| Station::new()).collect();
+        for st in &mut self.stations {
+            st.effective_fail_rate = self.fail_rate;
+        }
         self.jobs_total = n_jobs;
         self.jobs_completed = 0;
         self.job_queue = (0..n_jobs).collect();
@@ -207,6 +517,16 @@ impl FactorySim {
         self.t_last_decision = 0.0;
         self.last_event_type = None;
         self.last_event_sid = None;
+        self.maintenance_downtime = 0.0;
+        self.planned_downtime_count = 0;
+        self.unplanned_downtime_count = 0;
+        self.stream_control.store(STREAM_RUNNING, AtomicOrdering::SeqCst);
+
+        if self.maint_interval.is_some() {
+            for sid in 0..self.n_stations {
+                self.schedule_next_maintenance(sid);
+            }
+        }
 
         self.apply_action(None);
         self.get_snapshot(py)
@@ -243,22 +563,30 @@ impl FactorySim {
                     continue;
                 }
 
-                let can_pull = if i == 0 {
-                    !self.job_queue.is_empty()
+                let job_id = if self.stations[i].held_part {
+                    // Resume the part this station was evicted from holding
+                    // directly, rather than re-queuing it through the
+                    // upstream buffer and immediately re-pulling it.
+                    self.stations[i].held_part = false;
+                    None
                 } else {
-                    self.buffers[i - 1] > 0
-                };
+                    let can_pull = if i == 0 {
+                        !self.job_queue.is_empty()
+                    } else {
+                        self.buffers[i - 1] > 0
+                    };
 
-                if !can_pull {
-                    self.stations[i].starved = true;
-                    continue;
-                }
+                    if !can_pull {
+                        self.stations[i].starved = true;
+                        continue;
+                    }
 
-                let job_id = if i == 0 {
-                    self.job_queue.pop_front()
-                } else {
-                    self.buffers[i - 1] -= 1;
-                    None
+                    if i == 0 {
+                        self.job_queue.pop_front()
+                    } else {
+                        self.buffers[i - 1] -= 1;
+                        None
+                    }
                 };
 
                 let dur = self.sample_proc_time(i, self.current_speed);
@@ -270,7 +598,7 @@ impl FactorySim {
                     st.end_time = Some(self.time + dur);
                 }
                 self.schedule(self.time + dur, EventType::ServiceComplete, i);
-                if self.rng.gen::<f64>() < self.fail_rate {
+                if self.rng.gen::<f64>() < self.stations[i].effective_fail_rate {
                     let fail_t = self.time + self.rng.gen_range(0.0..dur);
                     self.schedule(fail_t, EventType::MachineFailure, i);
                 }
@@ -285,12 +613,14 @@ impl FactorySim {
 
     fn run_until_next_decision(&mut self, py: Python) -> PyResult<PyObject> {
         self.throughput_since_decision = 0;
-        while let Some(Reverse(evt)) = self.event_queue.pop() {
+        while let Some(evt) = self.event_queue.pop() {
             self.advance_time(evt.t);
             let handled = match evt.etype {
                 EventType::ServiceComplete => self.handle_service_complete(evt.sid),
                 EventType::MachineFailure => self.handle_machine_failure(evt.sid),
                 EventType::RepairComplete => self.handle_repair_complete(evt.sid),
+                EventType::MaintenanceDue => self.handle_maintenance_due(evt.sid),
+                EventType::MaintenanceComplete => self.handle_maintenance_complete(evt.sid),
             };
 
             if handled {
@@ -327,7 +657,7 @@ impl FactorySim {
             } else {
                 0.0
             };
-            let repair_remaining = if st.status == STATUS_DOWN {
+            let repair_remaining = if st.status == STATUS_DOWN || st.status == STATUS_MAINT {
                 (st.repair_eta.unwrap_or(self.time) - self.time).max(0.0)
             } else {
                 0.0
@@ -341,6 +671,7 @@ impl FactorySim {
             st_obj.set_item("down", st.status == STATUS_DOWN)?;
             st_obj.set_item("repairing", st.repairing)?;
             st_obj.set_item("repair_remaining", repair_remaining)?;
+            st_obj.set_item("maintenance", st.status == STATUS_MAINT)?;
             stations.append(st_obj)?;
 
             if st.status == STATUS_WORKING {
@@ -392,17 +723,33 @@ impl FactorySim {
         out.set_item("avg_processing_time", avg_proc_time)?;
         out.set_item("avg_processing_speed", avg_proc_speed)?;
 
+        let mut queued: Vec<usize> = self.repair_queue.iter().copied().collect();
+        if self.repair_policy == RepairPolicy::Bottleneck {
+            queued.sort_by(|&a, &b| {
+                self.repair_weight(b)
+                    .partial_cmp(&self.repair_weight(a))
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        let repair_priority = PyList::empty(py);
+        for sid in queued {
+            repair_priority.append(sid)?;
+        }
+        out.set_item("repair_priority", repair_priority)?;
+
         Ok(out.into())
     }
 
     fn run_to_finish(&mut self, py: Python) -> PyResult<PyObject> {
         while self.jobs_completed < self.jobs_total && !self.event_queue.is_empty() {
-            let Reverse(evt) = self.event_queue.pop().unwrap();
+            let evt = self.event_queue.pop().unwrap();
             self.advance_time(evt.t);
             let handled = match evt.etype {
                 EventType::ServiceComplete => self.handle_service_complete(evt.sid),
                 EventType::MachineFailure => self.handle_machine_failure(evt.sid),
                 EventType::RepairComplete => self.handle_repair_complete(evt.sid),
+                EventType::MaintenanceDue => self.handle_maintenance_due(evt.sid),
+                EventType::MaintenanceComplete => self.handle_maintenance_complete(evt.sid),
             };
 
             if handled {
@@ -454,18 +801,266 @@ impl FactorySim {
         out.set_item("down_stations", down_stations)?;
         out.set_item("workers_available", self.workers_available)?;
         out.set_item("workers_total", self.workers_total)?;
+        out.set_item("maintenance_downtime", self.maintenance_downtime)?;
+        let planned_vs_unplanned = PyDict::new(py);
+        planned_vs_unplanned.set_item("planned", self.planned_downtime_count)?;
+        planned_vs_unplanned.set_item("unplanned", self.unplanned_downtime_count)?;
+        out.set_item("planned_vs_unplanned", planned_vs_unplanned)?;
         Ok(out.into())
     }
+
+    /// Checks the sim's internal bookkeeping for consistency, returning one
+    /// human-readable description per violation found (empty if healthy).
+    /// Intended for use by `fuzz` and by callers exercising the bindings
+    /// directly from Python, not for anything on the hot path.
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        let parts_in_stations = self
+            .stations
+            .iter()
+            .filter(|s| s.status == STATUS_WORKING || s.has_finished_part || s.held_part)
+            .count();
+        let buffered: usize = self.buffers.iter().sum();
+        let accounted = self.jobs_completed + self.job_queue.len() + buffered + parts_in_stations;
+        if accounted != self.jobs_total {
+            violations.push(format!(
+                "part conservation violated: jobs_completed({}) + job_queue({}) + buffers({}) + in_stations({}) = {} != jobs_total({})",
+                self.jobs_completed,
+                self.job_queue.len(),
+                buffered,
+                parts_in_stations,
+                accounted,
+                self.jobs_total
+            ));
+        }
+
+        for (i, &cap) in self.buffer_caps.iter().enumerate() {
+            if self.buffers[i] > cap {
+                violations.push(format!(
+                    "buffer {i} holds {} parts, exceeding cap {cap}",
+                    self.buffers[i]
+                ));
+            }
+        }
+
+        let workers_in_use = self.stations.iter().filter(|s| s.repairing).count();
+        if self.workers_available + workers_in_use != self.workers_total {
+            violations.push(format!(
+                "worker accounting violated: workers_available({}) + workers_in_use({}) != workers_total({})",
+                self.workers_available, workers_in_use, self.workers_total
+            ));
+        }
+
+        for (i, st) in self.stations.iter().enumerate() {
+            if st.status == STATUS_WORKING && !self.event_queue.contains(EventType::ServiceComplete, i) {
+                violations.push(format!(
+                    "station {i} is WORKING but has no scheduled ServiceComplete"
+                ));
+            }
+            if (st.status == STATUS_DOWN || st.status == STATUS_MAINT)
+                && !st.repairing
+                && self.workers_available > 0
+            {
+                violations.push(format!(
+                    "station {i} is down/in maintenance and unattended while a worker is free"
+                ));
+            }
+        }
+
+        violations
+    }
+
+    /// Serializes the complete sim state (RNG, event queue, buffers,
+    /// stations, clock) to a compact binary blob. Restoring it with
+    /// `load_state` reproduces a bit-identical continuation, so callers can
+    /// checkpoint before a decision, try several `apply_action` values, and
+    /// rewind to explore alternatives deterministically.
+    fn save_state<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let bytes = bincode::serialize(self)
+            .map_err(|e| PyValueError::new_err(format!("failed to serialize sim state: {e}")))?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    fn load_state(&mut self, bytes: &[u8]) -> PyResult<()> {
+        *self = bincode::deserialize(bytes)
+            .map_err(|e| PyValueError::new_err(format!("failed to deserialize sim state: {e}")))?;
+        Ok(())
+    }
+
+    /// Returns a new, independent `FactorySim` at the same state as this
+    /// one, for exploring several continuations in parallel. Gets its own
+    /// fresh `stream_control` rather than sharing the original's, so
+    /// pausing/cancelling one's `run_realtime` stream can't reach into the
+    /// other.
+    fn clone_sim(&self) -> FactorySim {
+        let mut sim = self.clone();
+        sim.stream_control = Self::fresh_stream_control();
+        sim
+    }
+
+    /// Hands out a `StreamControl` bound to this sim's pause/resume/cancel
+    /// flag. Call this before starting `run_realtime` on a background
+    /// thread and keep the handle on the calling thread -- it's a
+    /// separate Python object, so steering the stream through it never
+    /// contends with the borrow `run_realtime` holds on `self`.
+    fn stream_control_handle(&self) -> StreamControl {
+        StreamControl {
+            flag: Arc::clone(&self.stream_control),
+        }
+    }
+
+    /// Advances the sim toward `horizon` in lockstep with wall-clock time:
+    /// one unit of simulated time takes `1 / (time_scale * current_speed)`
+    /// seconds of real time, so cranking `current_speed` up speeds up the
+    /// live feed too. Calls `callback(get_snapshot())` every `emit_every`
+    /// simulated time units and also right after any event fires in
+    /// between. Checked every loop iteration against `stream_control`, so a
+    /// `StreamControl` handle obtained beforehand can pause, resume, or
+    /// cancel the stream from another thread while this one sleeps with
+    /// the GIL released. Returns the final snapshot.
+    #[pyo3(signature = (horizon, time_scale, callback, emit_every=1.0))]
+    fn run_realtime(
+        &mut self,
+        py: Python,
+        horizon: f64,
+        time_scale: f64,
+        callback: PyObject,
+        emit_every: f64,
+    ) -> PyResult<PyObject> {
+        if time_scale <= 0.0 {
+            return Err(PyValueError::new_err("time_scale must be positive"));
+        }
+        if emit_every <= 0.0 {
+            return Err(PyValueError::new_err("emit_every must be positive"));
+        }
+
+        self.stream_control.store(STREAM_RUNNING, AtomicOrdering::SeqCst);
+        let mut next_emit = self.time;
+        let mut last_emit_time = f64::NEG_INFINITY;
+
+        loop {
+            loop {
+                match self.stream_control.load(AtomicOrdering::SeqCst) {
+                    STREAM_CANCELLED => return self.get_snapshot(py),
+                    STREAM_PAUSED => py.allow_threads(|| thread::sleep(Duration::from_millis(50))),
+                    _ => break,
+                }
+            }
+
+            if self.time >= next_emit {
+                let snap = self.get_snapshot(py)?;
+                callback.call1(py, (snap,))?;
+                next_emit = self.time + emit_every;
+                last_emit_time = self.time;
+            }
+
+            if self.time >= horizon {
+                break;
+            }
+
+            let next_event_t = self.event_queue.peek_time();
+            let wake_t = next_event_t.unwrap_or(horizon).min(horizon).min(next_emit);
+            let pace = (time_scale * self.current_speed).max(1e-9);
+            let wall_seconds = (wake_t - self.time).max(0.0) / pace;
+            if wall_seconds > 0.0 {
+                py.allow_threads(|| thread::sleep(Duration::from_secs_f64(wall_seconds)));
+            }
+
+            if self.stream_control.load(AtomicOrdering::SeqCst) == STREAM_CANCELLED {
+                continue;
+            }
+
+            match next_event_t {
+                Some(t) if t <= wake_t + 1e-9 => {
+                    let evt = self.event_queue.pop().unwrap();
+                    self.advance_time(evt.t);
+                    let handled = match evt.etype {
+                        EventType::ServiceComplete => self.handle_service_complete(evt.sid),
+                        EventType::MachineFailure => self.handle_machine_failure(evt.sid),
+                        EventType::RepairComplete => self.handle_repair_complete(evt.sid),
+                        EventType::MaintenanceDue => self.handle_maintenance_due(evt.sid),
+                        EventType::MaintenanceComplete => self.handle_maintenance_complete(evt.sid),
+                    };
+                    if handled {
+                        if self.record_history {
+                            let wip = self.buffers.iter().sum::<usize>()
+                                + self
+                                    .stations
+                                    .iter()
+                                    .filter(|s| s.status != STATUS_IDLE)
+                                    .count();
+                            self.wip_history.push(wip);
+                        }
+                        self.apply_action(None);
+                        // Force the top-of-loop cadence check to emit on the
+                        // very next iteration, so a live dashboard sees this
+                        // event right away instead of waiting for the next
+                        // `emit_every` boundary.
+                        next_emit = self.time;
+                    }
+                }
+                _ => self.advance_time(wake_t),
+            }
+        }
+
+        // An event between ticks can shift `next_emit` past `horizon`
+        // (see above), skipping the cadence tick that would normally land
+        // on it. Emit once more here so callers always see a final
+        // snapshot at the horizon, not just whatever the last in-stream
+        // emission happened to catch.
+        if self.time > last_emit_time {
+            let snap = self.get_snapshot(py)?;
+            callback.call1(py, (snap,))?;
+        }
+
+        self.get_snapshot(py)
+    }
+}
+
+/// Handle to an in-flight `run_realtime` stream's pause/resume/cancel flag.
+/// Deliberately a separate `#[pyclass]` rather than methods on
+/// `FactorySim` itself: `run_realtime` holds `self` for the whole stream,
+/// and PyO3 enforces that borrow at runtime independent of the GIL, so a
+/// UI thread steering the stream needs its own object to call into instead
+/// of reaching back through the same `FactorySim` instance.
+#[pyclass]
+#[derive(Clone)]
+struct StreamControl {
+    flag: Arc<AtomicU8>,
+}
+
+#[pymethods]
+impl StreamControl {
+    fn pause(&self) {
+        self.flag.store(STREAM_PAUSED, AtomicOrdering::SeqCst);
+    }
+
+    fn resume(&self) {
+        self.flag.store(STREAM_RUNNING, AtomicOrdering::SeqCst);
+    }
+
+    fn cancel(&self) {
+        self.flag.store(STREAM_CANCELLED, AtomicOrdering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.flag.load(AtomicOrdering::SeqCst) == STREAM_CANCELLED
+    }
 }
 
 impl FactorySim {
+    fn fresh_stream_control() -> Arc<AtomicU8> {
+        Arc::new(AtomicU8::new(STREAM_RUNNING))
+    }
+
     fn schedule(&mut self, t: f64, etype: EventType, sid: usize) {
-        self.event_queue.push(Reverse(Event {
+        self.event_queue.push(Event {
             t,
             seq: self.seq,
             etype,
             sid,
-        }));
+        });
         self.seq += 1;
     }
 
@@ -477,11 +1072,47 @@ impl FactorySim {
         if dt > 0.0 {
             let decay = (1.0 - self.util_alpha).powf(dt);
             for st in &mut self.stations {
-                let busy = if st.status == STATUS_WORKING { 1.0 } else { 0.0 };
-                st.util_ema = st.util_ema * decay + (1.0 - decay) * busy;
+                let busy = st.status == STATUS_WORKING;
+                st.util_ema = st.util_ema * decay + (1.0 - decay) * if busy { 1.0 } else { 0.0 };
+                if busy {
+                    st.busy_time_since_maint += dt;
+                    if self.wear_rate > 0.0 {
+                        st.effective_fail_rate =
+                            (self.fail_rate + self.wear_rate * st.busy_time_since_maint).min(1.0);
+                    }
+                }
+            }
+            self.time = to_time;
+            self.check_condition_maintenance();
+        } else {
+            self.time = to_time;
+        }
+    }
+
+    /// Brings preventive maintenance forward (by scheduling an immediate
+    /// `MaintenanceDue` event) for any station whose `util_ema` or
+    /// `busy_time_since_maint` has crossed the configured threshold.
+    fn check_condition_maintenance(&mut self) {
+        if self.maint_interval.is_none() {
+            return;
+        }
+        if self.maint_util_threshold.is_none() && self.maint_busy_threshold.is_none() {
+            return;
+        }
+        for sid in 0..self.n_stations {
+            let st = &self.stations[sid];
+            if st.maint_pending || st.maint_requested || st.status == STATUS_DOWN {
+                continue;
+            }
+            let crossed = self.maint_util_threshold.is_some_and(|th| st.util_ema >= th)
+                || self
+                    .maint_busy_threshold
+                    .is_some_and(|th| st.busy_time_since_maint >= th);
+            if crossed {
+                self.stations[sid].maint_requested = true;
+                self.schedule(self.time, EventType::MaintenanceDue, sid);
             }
         }
-        self.time = to_time;
     }
 
     fn sample_proc_time(&mut self, station_idx: usize, speed: f64) -> f64 {
@@ -545,8 +1176,10 @@ impl FactorySim {
             if let Some(job_id) = self.stations[sid].job_id.take() {
                 self.job_queue.push_front(job_id);
             }
-        } else {
+        } else if self.buffers[sid - 1] < self.buffer_caps[sid - 1] {
             self.buffers[sid - 1] += 1;
+        } else {
+            self.stations[sid].held_part = true;
         }
 
         {
@@ -559,11 +1192,10 @@ impl FactorySim {
             st.repair_eta = None;
         }
 
-        if self.workers_available > 0 {
-            self.assign_repair_worker(sid);
-        } else if !self.repair_queue.contains(&sid) {
+        if !self.repair_queue.contains(&sid) {
             self.repair_queue.push_back(sid);
         }
+        self.dispatch_repairs();
         true
     }
 
@@ -585,32 +1217,1041 @@ impl FactorySim {
             st.repair_eta = None;
         }
         self.workers_available = (self.workers_available + 1).min(self.workers_total);
-        if let Some(next_sid) = self.repair_queue.pop_front() {
-            if !self.assign_repair_worker(next_sid) {
-                self.repair_queue.push_front(next_sid);
+        self.unplanned_downtime_count += 1;
+        self.dispatch_repairs();
+        true
+    }
+
+    fn handle_maintenance_due(&mut self, sid: usize) -> bool {
+        if sid >= self.n_stations {
+            return false;
+        }
+        self.stations[sid].maint_requested = false;
+        if self.stations[sid].maint_pending {
+            // already claimed this cycle, either queued/under way or by an
+            // earlier condition-based trigger still waiting to take effect
+            return false;
+        }
+        let status = self.stations[sid].status;
+        if status == STATUS_DOWN {
+            // mid unplanned repair; try again next cycle instead of
+            // contending with the repair for this station
+            self.schedule_next_maintenance(sid);
+            return false;
+        }
+
+        if status == STATUS_WORKING {
+            if sid == 0 {
+                if let Some(job_id) = self.stations[sid].job_id.take() {
+                    self.job_queue.push_front(job_id);
+                }
+            } else if self.buffers[sid - 1] < self.buffer_caps[sid - 1] {
+                self.buffers[sid - 1] += 1;
+            } else {
+                self.stations[sid].held_part = true;
+            }
+        }
+
+        {
+            let st = &mut self.stations[sid];
+            st.status = STATUS_MAINT;
+            st.starved = false;
+            if status == STATUS_WORKING {
+                st.has_finished_part = false;
+                st.end_time = None;
             }
+            st.repairing = false;
+            st.repair_eta = None;
+            st.maint_pending = true;
+        }
+
+        if !self.repair_queue.contains(&sid) {
+            self.repair_queue.push_back(sid);
         }
+        self.dispatch_repairs();
         true
     }
 
+    fn handle_maintenance_complete(&mut self, sid: usize) -> bool {
+        if sid >= self.n_stations {
+            return false;
+        }
+        if self.stations[sid].status != STATUS_MAINT {
+            return false;
+        }
+
+        let resume_blocked = self.stations[sid].has_finished_part;
+        {
+            let st = &mut self.stations[sid];
+            st.status = if resume_blocked {
+                STATUS_BLOCKED
+            } else {
+                STATUS_IDLE
+            };
+            st.starved = false;
+            st.end_time = None;
+            st.repairing = false;
+            st.repair_eta = None;
+            st.maint_pending = false;
+            st.busy_time_since_maint = 0.0;
+            st.effective_fail_rate = self.fail_rate;
+            // Without this, a station whose steady-state utilization sits
+            // at/above `maint_util_threshold` gets pulled right back into
+            // `MaintenanceDue` on the very next `advance_time`, since
+            // `check_condition_maintenance` rechecks the same EMA that just
+            // triggered it.
+            st.util_ema = 0.0;
+        }
+        self.workers_available = (self.workers_available + 1).min(self.workers_total);
+        self.maintenance_downtime += self.maint_time;
+        self.planned_downtime_count += 1;
+        self.schedule_next_maintenance(sid);
+        self.dispatch_repairs();
+        true
+    }
+
+    /// Schedules this station's next `MaintenanceDue` event, desynchronized
+    /// from its siblings by a random `uniform(0, maint_jitter)` offset.
+    fn schedule_next_maintenance(&mut self, sid: usize) {
+        let Some(interval) = self.maint_interval else {
+            return;
+        };
+        let jitter = if self.maint_jitter > 0.0 {
+            self.rng.gen_range(0.0..self.maint_jitter)
+        } else {
+            0.0
+        };
+        self.schedule(self.time + interval + jitter, EventType::MaintenanceDue, sid);
+    }
+
+    /// Hands free workers to the highest-priority downed stations in
+    /// `repair_queue` under the active `repair_policy`, keeping queue order
+    /// as the tie-break.
+    fn dispatch_repairs(&mut self) {
+        while self.workers_available > 0 {
+            let Some(next_sid) = self.pop_next_repair() else {
+                break;
+            };
+            self.assign_repair_worker(next_sid);
+        }
+    }
+
+    fn pop_next_repair(&mut self) -> Option<usize> {
+        match self.repair_policy {
+            RepairPolicy::Fifo => self.repair_queue.pop_front(),
+            RepairPolicy::Bottleneck => {
+                // Not `Iterator::max_by`: it returns the *last* maximal
+                // element, so stations tying on weight (the common case)
+                // would dispatch LIFO. Keep the first maximal element
+                // instead, so ties fall back to queue (FIFO) order, same
+                // as the `repair_priority` list in `get_snapshot`.
+                let mut best_idx: Option<usize> = None;
+                let mut best_weight = f64::NEG_INFINITY;
+                for (idx, &sid) in self.repair_queue.iter().enumerate() {
+                    let weight = self.repair_weight(sid);
+                    if weight > best_weight {
+                        best_weight = weight;
+                        best_idx = Some(idx);
+                    }
+                }
+                self.repair_queue.remove(best_idx?)
+            }
+        }
+    }
+
+    /// How urgently `sid` should be repaired next: the upstream buffer it
+    /// gates (parts piling up behind it) plus the processing time of every
+    /// downstream station that is idle/starved and stalled waiting on it, so
+    /// a failure starving a slow bottleneck machine outranks one starving a
+    /// fast one.
+    fn repair_weight(&self, sid: usize) -> f64 {
+        let upstream_buffer_fill = if sid > 0 {
+            self.buffers[sid - 1] as f64
+        } else {
+            0.0
+        };
+        let downstream_starvation_risk: f64 = ((sid + 1)..self.n_stations)
+            .filter(|&j| self.stations[j].starved || self.stations[j].status == STATUS_IDLE)
+            .map(|j| self.proc_means[j])
+            .sum();
+        upstream_buffer_fill + downstream_starvation_risk
+    }
+
     fn assign_repair_worker(&mut self, sid: usize) -> bool {
         if sid >= self.n_stations || self.workers_available == 0 {
             return false;
         }
-        if self.stations[sid].status != STATUS_DOWN || self.stations[sid].repairing {
+        let is_maint = self.stations[sid].maint_pending;
+        let ready = if is_maint {
+            self.stations[sid].status == STATUS_MAINT
+        } else {
+            self.stations[sid].status == STATUS_DOWN
+        };
+        if !ready || self.stations[sid].repairing {
             return false;
         }
+        let (duration, etype) = if is_maint {
+            (self.maint_time, EventType::MaintenanceComplete)
+        } else {
+            (self.repair_time, EventType::RepairComplete)
+        };
         self.stations[sid].repairing = true;
-        self.stations[sid].repair_eta = Some(self.time + self.repair_time);
+        self.stations[sid].repair_eta = Some(self.time + duration);
         self.workers_available -= 1;
-        self.schedule(self.time + self.repair_time, EventType::RepairComplete, sid);
+        self.schedule(self.time + duration, etype, sid);
         true
     }
 }
 
+/// One randomized trial for `fuzz`: a fully-specified `FactorySim`
+/// construction plus a pre-rolled sequence of `apply_action` speed
+/// multipliers. Pre-rolling the actions (rather than sampling them live)
+/// lets `shrink` truncate a failing run's steps or stations and replay the
+/// exact same trial deterministically.
+#[derive(Clone)]
+struct FuzzConfig {
+    n_stations: usize,
+    buffer_caps: Vec<usize>,
+    proc_means: Vec<f64>,
+    proc_dists: Vec<String>,
+    util_alpha: f64,
+    fail_rate: f64,
+    repair_time: f64,
+    workers: usize,
+    n_jobs: usize,
+    seed: u64,
+    actions: Vec<Option<f64>>,
+    /// "fifo" or "bottleneck" -- randomized so the fuzzer exercises
+    /// `pop_next_repair`'s `Bottleneck` arm, not just the FIFO default.
+    repair_policy: String,
+    maint_interval: Option<f64>,
+    maint_jitter: f64,
+    maint_time: f64,
+    maint_util_threshold: Option<f64>,
+    maint_busy_threshold: Option<f64>,
+    wear_rate: f64,
+}
+
+impl FuzzConfig {
+    fn random(rng: &mut ChaCha12Rng, max_steps: usize) -> Self {
+        let n_stations: usize = rng.gen_range(1..=8);
+        let buffer_caps = (0..n_stations.saturating_sub(1))
+            .map(|_| rng.gen_range(1..=5))
+            .collect();
+        let proc_means = (0..n_stations).map(|_| rng.gen_range(0.5..5.0)).collect();
+        let proc_dists = (0..n_stations)
+            .map(|_| {
+                if rng.gen_bool(0.5) {
+                    "exp".to_string()
+                } else {
+                    "uniform".to_string()
+                }
+            })
+            .collect();
+        let n_actions = rng.gen_range(1..=max_steps.max(1));
+        let actions = (0..n_actions)
+            .map(|_| {
+                if rng.gen_bool(0.2) {
+                    Some(rng.gen_range(0.25..4.0))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let repair_policy = if rng.gen_bool(0.5) {
+            "bottleneck".to_string()
+        } else {
+            "fifo".to_string()
+        };
+
+        // Maintenance is only half-enabled so the fuzzer also covers
+        // reactive-repair-only configs, same as a caller who never passes
+        // `maint_interval`.
+        let maint_interval = if rng.gen_bool(0.5) {
+            Some(rng.gen_range(5.0..50.0))
+        } else {
+            None
+        };
+        let maint_jitter = if maint_interval.is_some() {
+            rng.gen_range(0.0..5.0)
+        } else {
+            0.0
+        };
+        let maint_util_threshold = if rng.gen_bool(0.3) {
+            Some(rng.gen_range(0.5..0.95))
+        } else {
+            None
+        };
+        let maint_busy_threshold = if rng.gen_bool(0.3) {
+            Some(rng.gen_range(5.0..50.0))
+        } else {
+            None
+        };
+        let wear_rate = if rng.gen_bool(0.5) {
+            rng.gen_range(0.0..0.05)
+        } else {
+            0.0
+        };
+
+        Self {
+            n_stations,
+            buffer_caps,
+            proc_means,
+            proc_dists,
+            util_alpha: rng.gen_range(0.01..0.5),
+            fail_rate: rng.gen_range(0.0..0.2),
+            repair_time: rng.gen_range(0.5..5.0),
+            workers: rng.gen_range(1..=3),
+            n_jobs: rng.gen_range(10..=50),
+            seed: rng.gen(),
+            actions,
+            repair_policy,
+            maint_interval,
+            maint_jitter,
+            maint_time: rng.gen_range(0.5..5.0),
+            maint_util_threshold,
+            maint_busy_threshold,
+            wear_rate,
+        }
+    }
+
+    fn build(&self) -> PyResult<FactorySim> {
+        FactorySim::new(
+            self.n_stations,
+            self.buffer_caps.clone(),
+            self.proc_means.clone(),
+            self.proc_dists.clone(),
+            self.util_alpha,
+            self.fail_rate,
+            self.repair_time,
+            self.workers,
+            Some(self.repair_policy.clone()),
+            self.maint_interval,
+            self.maint_jitter,
+            self.maint_time,
+            self.maint_util_threshold,
+            self.maint_busy_threshold,
+            self.wear_rate,
+        )
+    }
+}
+
+/// Runs one `FuzzConfig` trial to completion (or to its first invariant
+/// violation), returning the violations found at the failing step, if any.
+fn run_fuzz_trial(py: Python, cfg: &FuzzConfig) -> PyResult<Option<Vec<String>>> {
+    let mut sim = cfg.build()?;
+    sim.reset(py, Some(cfg.seed), cfg.n_jobs, None)?;
+
+    let violations = sim.check_invariants();
+    if !violations.is_empty() {
+        return Ok(Some(violations));
+    }
+
+    for &speed_mult in &cfg.actions {
+        sim.apply_action(speed_mult);
+        sim.run_until_next_decision(py)?;
+        let violations = sim.check_invariants();
+        if !violations.is_empty() {
+            return Ok(Some(violations));
+        }
+        if sim.jobs_completed >= sim.jobs_total {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Minimizes a failing `FuzzConfig` by repeatedly halving its step count and
+/// then decrementing its station count, keeping each shrink only if the
+/// smaller config still reproduces a violation.
+fn shrink(py: Python, mut cfg: FuzzConfig) -> PyResult<FuzzConfig> {
+    loop {
+        if cfg.actions.len() <= 1 {
+            break;
+        }
+        let mut candidate = cfg.clone();
+        candidate.actions.truncate(candidate.actions.len() / 2);
+        if run_fuzz_trial(py, &candidate)?.is_some() {
+            cfg = candidate;
+        } else {
+            break;
+        }
+    }
+
+    loop {
+        if cfg.n_stations <= 1 {
+            break;
+        }
+        let mut candidate = cfg.clone();
+        candidate.n_stations -= 1;
+        candidate.buffer_caps.truncate(candidate.n_stations.saturating_sub(1));
+        candidate.proc_means.truncate(candidate.n_stations);
+        candidate.proc_dists.truncate(candidate.n_stations);
+        if run_fuzz_trial(py, &candidate)?.is_some() {
+            cfg = candidate;
+        } else {
+            break;
+        }
+    }
+
+    Ok(cfg)
+}
+
+/// Randomized property-based test driver: builds `n_steps` random
+/// `FactorySim` configurations (station count, buffers, distributions,
+/// repair/maintenance parameters, and a sequence of `speed_mult` actions),
+/// running each under `check_invariants` until one fails. On the first
+/// failure, shrinks the offending configuration's step count and station
+/// count to a minimal repro and returns it as a dict; returns `None` if no
+/// violation turns up within `n_steps` trials.
+#[pyfunction]
+fn fuzz(py: Python, n_steps: usize, seed: u64) -> PyResult<PyObject> {
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+
+    for _ in 0..n_steps.max(1) {
+        let cfg = FuzzConfig::random(&mut rng, n_steps);
+        if let Some(violations) = run_fuzz_trial(py, &cfg)? {
+            let minimal = shrink(py, cfg)?;
+            let replay_violations = run_fuzz_trial(py, &minimal)?.unwrap_or(violations);
+
+            let out = PyDict::new(py);
+            out.set_item("n_stations", minimal.n_stations)?;
+            out.set_item("buffer_caps", minimal.buffer_caps.clone())?;
+            out.set_item("proc_means", minimal.proc_means.clone())?;
+            out.set_item("proc_dists", minimal.proc_dists.clone())?;
+            out.set_item("util_alpha", minimal.util_alpha)?;
+            out.set_item("fail_rate", minimal.fail_rate)?;
+            out.set_item("repair_time", minimal.repair_time)?;
+            out.set_item("workers", minimal.workers)?;
+            out.set_item("n_jobs", minimal.n_jobs)?;
+            out.set_item("seed", minimal.seed)?;
+            out.set_item("steps", minimal.actions.len())?;
+            out.set_item("repair_policy", minimal.repair_policy.clone())?;
+            out.set_item("maint_interval", minimal.maint_interval)?;
+            out.set_item("maint_jitter", minimal.maint_jitter)?;
+            out.set_item("maint_time", minimal.maint_time)?;
+            out.set_item("maint_util_threshold", minimal.maint_util_threshold)?;
+            out.set_item("maint_busy_threshold", minimal.maint_busy_threshold)?;
+            out.set_item("wear_rate", minimal.wear_rate)?;
+            out.set_item("violations", replay_violations)?;
+            return Ok(out.into());
+        }
+    }
+
+    Ok(py.None())
+}
+
 #[pymodule]
 fn mft_rust_sim(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<FactorySim>()?;
+    m.add_class::<StreamControl>()?;
+    m.add_function(wrap_pyfunction!(fuzz, m)?)?;
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyModule;
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    use std::time::Instant;
+
+    /// Drives the calendar queue and the `BinaryHeap<Reverse<Event>>` it
+    /// replaced through the same 50-station-line workload (a
+    /// `ServiceComplete` per station per tick, plus an occasional
+    /// `MachineFailure`, matching what `apply_action` actually schedules)
+    /// and checks both that pop order matches and that the calendar queue's
+    /// pop time stays within a generous multiple of the heap's.
+    #[test]
+    fn calendar_queue_matches_heap_order_and_keeps_up() {
+        const N_STATIONS: usize = 50;
+        const N_TICKS: usize = 2000;
+
+        let mut rng = ChaCha12Rng::seed_from_u64(42);
+        let mut events = Vec::new();
+        let mut seq = 0u64;
+        let mut t = 0.0f64;
+        for _ in 0..N_TICKS {
+            for sid in 0..N_STATIONS {
+                let dur = rng.gen_range(0.1..5.0);
+                events.push(Event {
+                    t: t + dur,
+                    seq,
+                    etype: EventType::ServiceComplete,
+                    sid,
+                });
+                seq += 1;
+                if rng.gen_bool(0.1) {
+                    let fail_t = t + rng.gen_range(0.0..dur);
+                    events.push(Event {
+                        t: fail_t,
+                        seq,
+                        etype: EventType::MachineFailure,
+                        sid,
+                    });
+                    seq += 1;
+                }
+            }
+            t += rng.gen_range(0.5..2.0);
+        }
+
+        let mut calendar = CalendarQueue::new(CALENDAR_MIN_BUCKETS, CALENDAR_DEFAULT_WIDTH);
+        let mut heap: BinaryHeap<Reverse<Event>> = BinaryHeap::new();
+        for &evt in &events {
+            calendar.push(evt);
+            heap.push(Reverse(evt));
+        }
+
+        let calendar_start = Instant::now();
+        let mut calendar_order = Vec::with_capacity(events.len());
+        while let Some(evt) = calendar.pop() {
+            calendar_order.push((evt.t, evt.seq));
+        }
+        let calendar_elapsed = calendar_start.elapsed();
+
+        let heap_start = Instant::now();
+        let mut heap_order = Vec::with_capacity(events.len());
+        while let Some(Reverse(evt)) = heap.pop() {
+            heap_order.push((evt.t, evt.seq));
+        }
+        let heap_elapsed = heap_start.elapsed();
+
+        assert_eq!(
+            calendar_order, heap_order,
+            "calendar queue must pop events in the same order as the heap it replaced"
+        );
+
+        // A generous ceiling, not a tight perf assertion (CI hardware
+        // varies): this is a regression guard against bucket_width
+        // miscalibration degrading pop back toward an O(n_buckets) scan.
+        assert!(
+            calendar_elapsed <= heap_elapsed * 20 + std::time::Duration::from_millis(50),
+            "calendar queue pop took {calendar_elapsed:?}, heap took {heap_elapsed:?} -- check bucket_width calibration"
+        );
+    }
+
+    /// Interleaves push and pop over a long time horizon with a working
+    /// set that repeatedly grows past and shrinks back below `resize`'s
+    /// thresholds -- unlike the bulk-push-then-drain workload above, this
+    /// shape forces `resize` to run many times *after* the queue's
+    /// simulated time has advanced far from zero, which is exactly what
+    /// exposes a `resize` that resets `current_bucket`/`year_top` to the
+    /// start instead of re-anchoring to the current position: `pop` then
+    /// has to rescan one bucket-width at a time from zero back up to the
+    /// real minimum on every call, a cost proportional to elapsed time
+    /// rather than O(1).
+    #[test]
+    fn calendar_queue_keeps_up_across_repeated_resizes() {
+        const N_CYCLES: usize = 2000;
+        const BURST: usize = 40;
+
+        fn run_calendar(seed: u64) -> std::time::Duration {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            let mut queue = CalendarQueue::new(CALENDAR_MIN_BUCKETS, CALENDAR_DEFAULT_WIDTH);
+            let mut seq = 0u64;
+            let mut t = 0.0f64;
+            let start = Instant::now();
+            for _ in 0..N_CYCLES {
+                for sid in 0..BURST {
+                    let dur = rng.gen_range(0.1..5.0);
+                    queue.push(Event {
+                        t: t + dur,
+                        seq,
+                        etype: EventType::ServiceComplete,
+                        sid,
+                    });
+                    seq += 1;
+                }
+                for _ in 0..BURST {
+                    let evt = queue.pop().expect("burst should never run dry mid-drain");
+                    t = evt.t;
+                }
+            }
+            start.elapsed()
+        }
+
+        fn run_heap(seed: u64) -> std::time::Duration {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            let mut heap: BinaryHeap<Reverse<Event>> = BinaryHeap::new();
+            let mut seq = 0u64;
+            let mut t = 0.0f64;
+            let start = Instant::now();
+            for _ in 0..N_CYCLES {
+                for sid in 0..BURST {
+                    let dur = rng.gen_range(0.1..5.0);
+                    heap.push(Reverse(Event {
+                        t: t + dur,
+                        seq,
+                        etype: EventType::ServiceComplete,
+                        sid,
+                    }));
+                    seq += 1;
+                }
+                for _ in 0..BURST {
+                    let Reverse(evt) = heap.pop().expect("burst should never run dry mid-drain");
+                    t = evt.t;
+                }
+            }
+            start.elapsed()
+        }
+
+        let calendar_elapsed = run_calendar(11);
+        let heap_elapsed = run_heap(11);
+
+        assert!(
+            calendar_elapsed <= heap_elapsed * 5 + std::time::Duration::from_millis(50),
+            "calendar queue took {calendar_elapsed:?} across repeated resizes, heap took {heap_elapsed:?} -- check resize's current_bucket/year_top re-anchoring"
+        );
+    }
+
+    /// A preempted in-progress part (failure or maintenance) must not
+    /// overflow the upstream buffer it's handed back to: that buffer can
+    /// have been independently refilled to cap by upstream flow while the
+    /// station was down, so the hand-back has to be cap-checked the same
+    /// as any other write to `buffers`.
+    #[test]
+    fn preempted_part_never_overflows_upstream_buffer() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                3,
+                vec![2, 2],
+                vec![1.0, 1.0, 1.0],
+                vec!["exp".to_string(), "exp".to_string(), "exp".to_string()],
+                0.1,
+                0.0,
+                1.0,
+                3,
+                None,
+                Some(3.0),
+                0.0,
+                1.0,
+                None,
+                None,
+                0.0,
+            )
+            .unwrap();
+            sim.reset(py, Some(1), 200, None).unwrap();
+            for _ in 0..2000 {
+                if sim.jobs_completed >= sim.jobs_total {
+                    break;
+                }
+                sim.run_until_next_decision(py).unwrap();
+                let violations = sim.check_invariants();
+                assert!(violations.is_empty(), "invariant violated: {violations:?}");
+            }
+        });
+    }
+
+    /// Same hand-back bug, reachable through plain reactive failure alone
+    /// (no maintenance configured at all).
+    #[test]
+    fn preempted_part_never_overflows_upstream_buffer_reactive_only() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                3,
+                vec![2, 2],
+                vec![1.0, 1.0, 1.0],
+                vec!["exp".to_string(), "exp".to_string(), "exp".to_string()],
+                0.1,
+                0.2,
+                1.0,
+                3,
+                None,
+                None,
+                0.0,
+                1.0,
+                None,
+                None,
+                0.0,
+            )
+            .unwrap();
+            sim.reset(py, Some(1), 200, None).unwrap();
+            for _ in 0..2000 {
+                if sim.jobs_completed >= sim.jobs_total {
+                    break;
+                }
+                sim.run_until_next_decision(py).unwrap();
+                let violations = sim.check_invariants();
+                assert!(violations.is_empty(), "invariant violated: {violations:?}");
+            }
+        });
+    }
+
+    /// A station's `util_ema` has to reset on maintenance completion, or
+    /// `check_condition_maintenance` immediately re-triggers on the same
+    /// (still-crossed) EMA and the station livelocks in maintenance instead
+    /// of going back to work.
+    #[test]
+    fn maintenance_complete_resets_util_ema_to_avoid_livelock() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                1,
+                vec![],
+                vec![1.0],
+                vec!["exp".to_string()],
+                0.3,
+                0.0,
+                1.0,
+                1,
+                None,
+                Some(50.0),
+                0.0,
+                0.5,
+                Some(0.5),
+                None,
+                0.0,
+            )
+            .unwrap();
+            sim.reset(py, Some(7), 5000, None).unwrap();
+
+            let mut saw_maintenance_complete = false;
+            for _ in 0..500 {
+                if sim.jobs_completed >= sim.jobs_total {
+                    break;
+                }
+                sim.run_until_next_decision(py).unwrap();
+                if sim.last_event_type == Some(EventType::MaintenanceComplete) {
+                    saw_maintenance_complete = true;
+                    assert_eq!(
+                        sim.stations[0].util_ema, 0.0,
+                        "util_ema must reset on maintenance completion or the condition-based trigger livelocks"
+                    );
+                }
+            }
+            assert!(
+                saw_maintenance_complete,
+                "test never exercised a MaintenanceComplete event"
+            );
+            // Without the reset, this sim livelocks in maintenance almost
+            // immediately and barely any jobs finish in 500 decisions.
+            assert!(
+                sim.jobs_completed > 10,
+                "expected meaningful throughput, got {} jobs completed in 500 decisions (livelocked in maintenance?)",
+                sim.jobs_completed
+            );
+        });
+    }
+
+    /// `fuzz` is the invariant-checking harness meant to catch exactly the
+    /// bugs fixed elsewhere in this file, but it was never actually wired
+    /// into the test suite. Run it with a fixed seed and assert a clean
+    /// result so it gates future regressions instead of sitting unused.
+    #[test]
+    fn fuzz_finds_no_invariant_violations() {
+        Python::with_gil(|py| {
+            let result = fuzz(py, 300, 2024).unwrap();
+            assert!(
+                result.is_none(py),
+                "fuzz(py, 300, 2024) found an invariant violation: {:?}",
+                result.as_ref(py)
+            );
+        });
+    }
+
+    /// `pop_next_repair`'s `Bottleneck` arm must keep the first maximal
+    /// element, not the last, so stations tying on `repair_weight` (the
+    /// common case -- here, three downed stations with no upstream fill and
+    /// no starved downstream neighbor, so all three weigh 0.0) dispatch in
+    /// FIFO queue order instead of LIFO.
+    #[test]
+    fn bottleneck_repair_dispatch_breaks_ties_fifo() {
+        let mut sim = FactorySim::new(
+            3,
+            vec![0, 0],
+            vec![1.0, 1.0, 1.0],
+            vec!["exp".to_string(), "exp".to_string(), "exp".to_string()],
+            0.2,
+            0.0,
+            1.0,
+            3,
+            Some("bottleneck".to_string()),
+            None,
+            0.0,
+            1.0,
+            None,
+            None,
+            0.0,
+        )
+        .unwrap();
+
+        for sid in 0..3 {
+            sim.stations[sid].status = STATUS_DOWN;
+        }
+        sim.repair_queue = VecDeque::from([2, 0, 1]);
+
+        assert_eq!(sim.repair_weight(0), 0.0);
+        assert_eq!(sim.repair_weight(1), 0.0);
+        assert_eq!(sim.repair_weight(2), 0.0);
+
+        assert_eq!(
+            sim.pop_next_repair(),
+            Some(2),
+            "a 3-way tie on repair_weight must resolve to the first station in queue order"
+        );
+        assert_eq!(sim.pop_next_repair(), Some(0));
+        assert_eq!(sim.pop_next_repair(), Some(1));
+    }
+
+    /// `save_state`/`load_state` (which go through bincode) and `clone_sim`
+    /// (which goes through `Clone`) must reproduce a bit-identical continuation: a sim
+    /// restored from a checkpoint and a sim cloned from the same point have
+    /// to serialize to the exact same bytes after being driven through an
+    /// identical further sequence of decisions, including the RNG draws
+    /// those decisions consume.
+    #[test]
+    fn save_load_and_clone_reproduce_bit_identical_continuation() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                3,
+                vec![2, 2],
+                vec![1.0, 1.2, 0.8],
+                vec!["exp".to_string(), "uniform".to_string(), "exp".to_string()],
+                0.2,
+                0.1,
+                1.0,
+                2,
+                Some("bottleneck".to_string()),
+                Some(10.0),
+                1.0,
+                0.5,
+                None,
+                None,
+                0.01,
+            )
+            .unwrap();
+            sim.reset(py, Some(99), 150, None).unwrap();
+            for _ in 0..20 {
+                if sim.jobs_completed >= sim.jobs_total {
+                    break;
+                }
+                sim.run_until_next_decision(py).unwrap();
+            }
+
+            let checkpoint = sim.save_state(py).unwrap().as_bytes().to_vec();
+
+            let mut cloned = sim.clone_sim();
+            let mut restored = FactorySim::new(
+                1,
+                vec![],
+                vec![1.0],
+                vec!["exp".to_string()],
+                0.2,
+                0.0,
+                1.0,
+                1,
+                None,
+                None,
+                0.0,
+                1.0,
+                None,
+                None,
+                0.0,
+            )
+            .unwrap();
+            restored.load_state(&checkpoint).unwrap();
+
+            for _ in 0..50 {
+                let cloned_done = cloned.jobs_completed >= cloned.jobs_total;
+                let restored_done = restored.jobs_completed >= restored.jobs_total;
+                if cloned_done && restored_done {
+                    break;
+                }
+                cloned.run_until_next_decision(py).unwrap();
+                restored.run_until_next_decision(py).unwrap();
+            }
+
+            assert_eq!(
+                bincode::serialize(&cloned).unwrap(),
+                bincode::serialize(&restored).unwrap(),
+                "clone_sim and save_state/load_state diverged from a direct continuation of the same checkpoint"
+            );
+        });
+    }
+
+    /// `run_realtime` must drive the sim all the way to `horizon`, emitting
+    /// a snapshot via the callback at least once along the way. Uses a huge
+    /// `time_scale` so the wall-clock pacing this test is actually
+    /// exercising collapses to negligible real sleeps.
+    #[test]
+    fn run_realtime_reaches_horizon_and_emits_snapshots() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                2,
+                vec![2],
+                vec![1.0, 1.0],
+                vec!["exp".to_string(), "exp".to_string()],
+                0.2,
+                0.0,
+                1.0,
+                2,
+                None,
+                None,
+                0.0,
+                1.0,
+                None,
+                None,
+                0.0,
+            )
+            .unwrap();
+            sim.reset(py, Some(5), 500, None).unwrap();
+
+            let snapshots = PyList::empty(py);
+            let callback: PyObject = snapshots.getattr("append").unwrap().into();
+
+            let horizon = 10.0;
+            let result = sim
+                .run_realtime(py, horizon, 1e9, callback, 1.0)
+                .unwrap();
+
+            let t: f64 = result.as_ref(py).get_item("t").unwrap().extract().unwrap();
+            assert!(
+                t >= horizon - 1e-6,
+                "run_realtime returned before reaching its horizon: t={t}, horizon={horizon}"
+            );
+            assert_eq!(sim.time, t);
+            assert!(
+                !snapshots.is_empty(),
+                "callback was never invoked during the run"
+            );
+
+            let last: f64 = snapshots
+                .get_item(snapshots.len() - 1)
+                .unwrap()
+                .get_item("t")
+                .unwrap()
+                .extract()
+                .unwrap();
+            assert!(
+                last >= horizon - 1e-6,
+                "last emitted snapshot's time {last} should be at/past the horizon {horizon}"
+            );
+        });
+    }
+
+    /// The doc comment on `run_realtime` promises a callback right after any
+    /// event that fires between two cadence ticks, not just on the ticks
+    /// themselves. A high `fail_rate` makes a `MachineFailure` landing
+    /// strictly inside a `[k*emit_every, (k+1)*emit_every)` window all but
+    /// certain; if the emission is only cadence-gated, every snapshot's time
+    /// lands on a multiple of `emit_every`, so finding one that doesn't is
+    /// proof an event triggered an out-of-band emission.
+    #[test]
+    fn run_realtime_emits_promptly_between_cadence_ticks() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                2,
+                vec![2],
+                vec![1.0, 1.0],
+                vec!["exp".to_string(), "exp".to_string()],
+                0.2,
+                0.9,
+                0.5,
+                2,
+                None,
+                None,
+                0.0,
+                1.0,
+                None,
+                None,
+                0.0,
+            )
+            .unwrap();
+            sim.reset(py, Some(5), 500, None).unwrap();
+
+            let snapshots = PyList::empty(py);
+            let callback: PyObject = snapshots.getattr("append").unwrap().into();
+
+            let emit_every = 5.0;
+            let horizon = 15.0;
+            sim.run_realtime(py, horizon, 1e9, callback, emit_every)
+                .unwrap();
+
+            let off_cadence = (0..snapshots.len()).any(|i| {
+                let t: f64 = snapshots
+                    .get_item(i)
+                    .unwrap()
+                    .get_item("t")
+                    .unwrap()
+                    .extract()
+                    .unwrap();
+                let nearest_tick = (t / emit_every).round() * emit_every;
+                (t - nearest_tick).abs() > 1e-6
+            });
+            assert!(
+                off_cadence,
+                "every emitted snapshot landed exactly on an emit_every boundary; \
+                 no MachineFailure between ticks triggered a prompt out-of-band emission"
+            );
+        });
+    }
+
+    /// `StreamControl::cancel` is the backlog's named pause/resume/cancel
+    /// deliverable; this drives it from inside the `run_realtime` callback
+    /// itself (the only thread available in a synchronous test) and checks
+    /// the stream actually stops instead of running on to `horizon`.
+    #[test]
+    fn run_realtime_cancel_stops_before_horizon() {
+        Python::with_gil(|py| {
+            let mut sim = FactorySim::new(
+                2,
+                vec![2],
+                vec![1.0, 1.0],
+                vec!["exp".to_string(), "exp".to_string()],
+                0.2,
+                0.0,
+                1.0,
+                2,
+                None,
+                None,
+                0.0,
+                1.0,
+                None,
+                None,
+                0.0,
+            )
+            .unwrap();
+            sim.reset(py, Some(5), 500, None).unwrap();
+
+            let handle = sim.stream_control_handle();
+            let snapshots = PyList::empty(py);
+
+            let module = PyModule::from_code(
+                py,
+                "def make_callback(snapshots, handle):\n    \
+                     def cb(snap):\n        \
+                         snapshots.append(snap)\n        \
+                         handle.cancel()\n    \
+                     return cb\n",
+                "run_realtime_cancel_test_cb.py",
+                "run_realtime_cancel_test_cb",
+            )
+            .unwrap();
+            let callback: PyObject = module
+                .getattr("make_callback")
+                .unwrap()
+                .call1((snapshots, handle))
+                .unwrap()
+                .into();
+
+            let horizon = 1000.0;
+            let result = sim
+                .run_realtime(py, horizon, 1e9, callback, 1.0)
+                .unwrap();
+
+            let t: f64 = result.as_ref(py).get_item("t").unwrap().extract().unwrap();
+            assert!(
+                t < horizon,
+                "cancel() from within the callback should stop run_realtime \
+                 well short of horizon, got t={t}"
+            );
+            assert_eq!(
+                snapshots.len(),
+                1,
+                "cancel() should take effect before a second snapshot is emitted"
+            );
+        });
+    }
+}
+